@@ -1,26 +1,202 @@
-use zed_extension_api::{self as zed, Command, Result};
+use std::fs;
 
-struct FerruleExtension;
+use zed_extension_api::{
+    self as zed, settings::LspSettings, Command, LanguageServerId, Result, Worktree,
+};
+
+const LANGUAGE_SERVER_ID: &str = "ferrule-lsp";
+const FORMATTER_SERVER_ID: &str = "ferrule-fmt";
+
+struct FerruleExtension {
+    cached_binary_path: Option<String>,
+}
+
+impl FerruleExtension {
+    fn language_server_binary_path(
+        &mut self,
+        language_server_id: &LanguageServerId,
+        worktree: &Worktree,
+    ) -> Result<String> {
+        if let Some(path) = &self.cached_binary_path {
+            if fs::metadata(path).is_ok() {
+                return Ok(path.clone());
+            }
+        }
+
+        zed::set_language_server_installation_status(
+            language_server_id,
+            &zed::LanguageServerInstallationStatus::CheckingForUpdate,
+        );
+        let release = match zed::latest_github_release(
+            "karol-broda/ferrule",
+            zed::GithubReleaseOptions {
+                require_assets: true,
+                pre_release: false,
+            },
+        ) {
+            Ok(release) => release,
+            Err(_) => {
+                return worktree
+                    .which("ferrule-lsp")
+                    .ok_or_else(|| "ferrule-lsp is not on PATH and no release is available".to_string());
+            }
+        };
+
+        let (platform, arch) = zed::current_platform();
+        let asset_name = format!(
+            "ferrule-lsp-{version}-{arch}-{os}.{ext}",
+            version = release.version,
+            arch = match arch {
+                zed::Architecture::Aarch64 => "aarch64",
+                zed::Architecture::X86 => "x86",
+                zed::Architecture::X8664 => "x86_64",
+            },
+            os = match platform {
+                zed::Os::Mac => "apple-darwin",
+                zed::Os::Linux => "unknown-linux-gnu",
+                zed::Os::Windows => "pc-windows-msvc",
+            },
+            ext = match platform {
+                zed::Os::Mac | zed::Os::Linux => "tar.gz",
+                zed::Os::Windows => "zip",
+            },
+        );
+
+        let asset = release
+            .assets
+            .iter()
+            .find(|asset| asset.name == asset_name)
+            .ok_or_else(|| format!("no asset found matching {asset_name:?}"))?;
+
+        let version_dir = format!("ferrule-lsp-{}", release.version);
+        let binary_path = format!("{version_dir}/ferrule-lsp");
+
+        if !fs::metadata(&binary_path).is_ok_and(|stat| stat.is_file()) {
+            zed::set_language_server_installation_status(
+                language_server_id,
+                &zed::LanguageServerInstallationStatus::Downloading,
+            );
+
+            let file_kind = match platform {
+                zed::Os::Mac | zed::Os::Linux => zed::DownloadedFileType::GzipTar,
+                zed::Os::Windows => zed::DownloadedFileType::Zip,
+            };
+
+            zed::download_file(&asset.download_url, &version_dir, file_kind)
+                .map_err(|err| format!("failed to download file: {err}"))?;
+
+            zed::make_file_executable(&binary_path)?;
+
+            let entries = fs::read_dir(".")
+                .map_err(|err| format!("failed to list working directory: {err}"))?;
+            for entry in entries {
+                let entry = entry.map_err(|err| format!("failed to load directory entry: {err}"))?;
+                if entry.file_name().to_str() != Some(&version_dir) {
+                    fs::remove_dir_all(entry.path()).ok();
+                }
+            }
+        }
+
+        self.cached_binary_path = Some(binary_path.clone());
+        Ok(binary_path)
+    }
+
+    fn language_server_command_for(
+        &mut self,
+        language_server_id: &LanguageServerId,
+        worktree: &Worktree,
+        default_args: Vec<String>,
+    ) -> Result<Command> {
+        let lsp_settings = LspSettings::for_worktree(language_server_id.as_ref(), worktree).ok();
+        let binary_settings = lsp_settings.and_then(|settings| settings.binary);
+
+        if let Some(path) = binary_settings.as_ref().and_then(|binary| binary.path.clone()) {
+            return Ok(Command {
+                command: path,
+                args: binary_settings
+                    .and_then(|binary| binary.arguments)
+                    .unwrap_or(default_args),
+                env: worktree.shell_env(),
+            });
+        }
+
+        let path = self.language_server_binary_path(language_server_id, worktree)?;
+        Ok(Command {
+            command: path,
+            args: binary_settings
+                .and_then(|binary| binary.arguments)
+                .unwrap_or(default_args),
+            env: worktree.shell_env(),
+        })
+    }
+}
 
 impl zed::Extension for FerruleExtension {
     fn new() -> Self {
-        FerruleExtension
+        FerruleExtension {
+            cached_binary_path: None,
+        }
     }
 
     fn language_server_command(
         &mut self,
         language_server_id: &zed::LanguageServerId,
-        _worktree: &zed::Worktree,
+        worktree: &zed::Worktree,
     ) -> Result<Command> {
-        if language_server_id.as_ref() == "ferrule-lsp" {
-            Ok(Command {
-                command: "ferrule-lsp".to_string(),
-                args: vec![],
-                env: Default::default(),
-            })
-        } else {
-            Err(format!("unknown language server: {}", language_server_id.as_ref()))
+        match language_server_id.as_ref() {
+            LANGUAGE_SERVER_ID => self.language_server_command_for(language_server_id, worktree, vec![]),
+            FORMATTER_SERVER_ID => self.language_server_command_for(
+                language_server_id,
+                worktree,
+                vec!["--format".to_string(), "--stdin".to_string()],
+            ),
+            _ => Err(format!("unknown language server: {}", language_server_id.as_ref())),
+        }
+    }
+
+    fn language_server_workspace_configuration(
+        &mut self,
+        language_server_id: &zed::LanguageServerId,
+        worktree: &zed::Worktree,
+    ) -> Result<Option<serde_json::Value>> {
+        let overrides = LspSettings::for_worktree(language_server_id.as_ref(), worktree)
+            .ok()
+            .and_then(|lsp_settings| lsp_settings.settings);
+
+        Ok(Some(merge_with_defaults(default_workspace_settings(), overrides)))
+    }
+
+    fn language_server_initialization_options(
+        &mut self,
+        language_server_id: &zed::LanguageServerId,
+        worktree: &zed::Worktree,
+    ) -> Result<Option<serde_json::Value>> {
+        let initialization_options = LspSettings::for_worktree(language_server_id.as_ref(), worktree)
+            .ok()
+            .and_then(|lsp_settings| lsp_settings.initialization_options);
+
+        Ok(initialization_options)
+    }
+}
+
+fn default_workspace_settings() -> serde_json::Value {
+    serde_json::json!({
+        "lint": { "enabled": true },
+        "format": { "enabled": true },
+    })
+}
+
+fn merge_with_defaults(
+    defaults: serde_json::Value,
+    overrides: Option<serde_json::Value>,
+) -> serde_json::Value {
+    match (defaults, overrides) {
+        (serde_json::Value::Object(mut defaults), Some(serde_json::Value::Object(overrides))) => {
+            defaults.extend(overrides);
+            serde_json::Value::Object(defaults)
         }
+        (defaults, None) => defaults,
+        (_, Some(overrides)) => overrides,
     }
 }
 